@@ -26,17 +26,23 @@ extern crate byteorder;
 extern crate dirs;
 extern crate glob;
 extern crate openat;
+extern crate rayon;
+extern crate regex;
 
 use ansi_term::Colour::{Red, Yellow};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use openat::{Dir, SimpleType};
+use rayon::prelude::*;
+use regex::Regex;
 
 /* let's be academic and properly handle invalid Unicode filepaths, which
  * basically entails using OsString instead of String */
 
 struct Config {
     defaults: HashMap<String, String>,
-    patterns: Vec<Pattern>,
+    patterns: Vec<PatternEntry>,
+    /// `[ignore]` patterns: directories pruned from scanning outright, never recursed into
+    ignores: Vec<Matcher>,
 }
 
 impl Config {
@@ -44,6 +50,36 @@ impl Config {
         Config {
             defaults: HashMap::new(),
             patterns: Vec::new(),
+            ignores: Vec::new(),
+        }
+    }
+}
+
+/// One configured pattern, optionally negated (a leading `!`) to mark matching codebases as
+/// excluded from all results rather than as a candidate default.
+struct PatternEntry {
+    matcher: Matcher,
+    negated: bool,
+}
+
+/// The pattern-prefix matching language: `glob:` (the default when no prefix is given),
+/// `path:`, `rootfilesin:` and `re:`.
+enum Matcher {
+    Glob(Pattern),
+    /// exact path prefix, anchored at the scan root
+    Path(PathBuf),
+    /// matches codebases located directly inside the given directory
+    RootFilesIn(PathBuf),
+    Regex(Regex),
+}
+
+impl Matcher {
+    fn matches(&self, path: &Path) -> bool {
+        match self {
+            Matcher::Glob(pattern) => pattern.matches_path(path),
+            Matcher::Path(prefix) => path.starts_with(prefix),
+            Matcher::RootFilesIn(dir) => path.parent() == Some(dir.as_path()),
+            Matcher::Regex(re) => re.is_match(&path.to_string_lossy()),
         }
     }
 }
@@ -54,44 +90,185 @@ fn read_config() -> io::Result<Config> {
         None => return Ok(Config::new()),
     };
 
-    let file = match fs::File::open(&config_path) {
-        Ok(f) => f,
-        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Config::new()),
-        Err(e) => return Err(e),
-    };
+    if !config_path.exists() {
+        return Ok(Config::new());
+    }
 
     let mut config = Config::new();
+    let mut visited: HashSet<PathBuf> = HashSet::new();
+    load_config_file(&config_path, &mut config, &mut visited)?;
+    Ok(config)
+}
+
+fn invalid_config_line(line: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid config line: {}", line),
+    )
+}
+
+fn add_pattern(config: &mut Config, text: &str) -> io::Result<()> {
+    let (negated, text) = match text.strip_prefix('!') {
+        Some(rest) => (true, rest.trim_start()),
+        None => (false, text),
+    };
+    let matcher = parse_matcher(text)?;
+    config.patterns.push(PatternEntry { matcher, negated });
+    Ok(())
+}
+
+fn add_ignore_pattern(config: &mut Config, text: &str) -> io::Result<()> {
+    config.ignores.push(parse_matcher(text)?);
+    Ok(())
+}
+
+fn parse_matcher(text: &str) -> io::Result<Matcher> {
+    if let Some(rest) = text.strip_prefix("path:") {
+        Ok(Matcher::Path(PathBuf::from(rest)))
+    } else if let Some(rest) = text.strip_prefix("rootfilesin:") {
+        Ok(Matcher::RootFilesIn(PathBuf::from(rest)))
+    } else if let Some(rest) = text.strip_prefix("re:") {
+        Regex::new(rest)
+            .map(Matcher::Regex)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    } else {
+        let rest = text.strip_prefix("glob:").unwrap_or(text);
+        Pattern::new(rest)
+            .map(Matcher::Glob)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.msg))
+    }
+}
+
+/// Is `path` matched by any negated (`!`-prefixed) config pattern? Excluded codebases are
+/// removed from every result, like a DifferenceMatcher: the scanned set minus this one.
+fn is_excluded(config: &Config, path: &Path) -> bool {
+    config
+        .patterns
+        .iter()
+        .any(|entry| entry.negated && entry.matcher.matches(path))
+}
+
+/// Is `path` matched by any `[ignore]`/`.codeswitchignore` pattern? Such directories are
+/// pruned from the scan entirely: never recursed into, never listed.
+///
+/// Unlike pattern matching for codebase selection, a `glob:`/bare pattern with no `/` in it
+/// is gitignore-style: it matches `path`'s basename at any depth, not just a top-level
+/// component. A pattern containing a `/` still anchors against the full relative path, so
+/// e.g. `glob:**/node_modules` and plain `node_modules` prune the same set of directories.
+fn is_ignored(ignore: &[Matcher], path: &Path) -> bool {
+    ignore.iter().any(|matcher| match matcher {
+        Matcher::Glob(pattern) if !pattern.as_str().contains('/') => path
+            .file_name()
+            .is_some_and(|name| pattern.matches(&name.to_string_lossy())),
+        other => other.matches(path),
+    })
+}
+
+/// Load a single config file into `config`, following any `%include` directives it contains.
+/// A missing `%include`d file is a hard error; `visited` holds the canonicalized paths of
+/// files already loaded, so an include cycle is silently broken instead of recursing forever.
+fn load_config_file(
+    path: &Path,
+    config: &mut Config,
+    visited: &mut HashSet<PathBuf>,
+) -> io::Result<()> {
+    let canon = fs::canonicalize(path)?;
+    if !visited.insert(canon) {
+        return Ok(());
+    }
+
+    let file = fs::File::open(path)?;
     let reader = io::BufReader::new(file);
+    let base_dir = path.parent().unwrap_or_else(|| Path::new("."));
+
+    /* what the last non-continuation line added, so an indented continuation line knows
+     * whether to reject itself (after a default) or add another entry to the right pattern list */
+    enum LastEntry {
+        None,
+        Default,
+        Pattern,
+        Ignore,
+    }
+    let mut last_entry = LastEntry::None;
+    let mut section: Option<String> = None;
 
     for line in reader.lines() {
-        let line = line?;
-        let line = line.trim();
+        let raw = line?;
+        let trimmed = raw.trim();
 
         // skip empty lines and comments
-        if line.is_empty() || line.starts_with('#') {
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            last_entry = LastEntry::None;
+            continue;
+        }
+
+        // an indented line continues (adds another entry to) the previous pattern/ignore
+        // list; defaults are a single exact-match value, so there's nothing sensible to
+        // continue them onto
+        if raw.starts_with(char::is_whitespace) {
+            match &last_entry {
+                LastEntry::Default => return Err(invalid_config_line(trimmed)),
+                LastEntry::Ignore => add_ignore_pattern(config, trimmed)?,
+                LastEntry::Pattern | LastEntry::None => add_pattern(config, trimmed)?,
+            }
+            continue;
+        }
+
+        if let Some(rest) = trimmed.strip_prefix("%include") {
+            let rest = rest.trim();
+            if rest.is_empty() {
+                return Err(invalid_config_line(trimmed));
+            }
+            load_config_file(&base_dir.join(rest), config, visited)?;
+            last_entry = LastEntry::None;
+            continue;
+        }
+
+        if let Some(rest) = trimmed.strip_prefix("%unset") {
+            let name = rest.trim();
+            if name.is_empty() {
+                return Err(invalid_config_line(trimmed));
+            }
+            config.defaults.remove(name);
+            last_entry = LastEntry::None;
+            continue;
+        }
+
+        if let Some(rest) = trimmed.strip_prefix('[') {
+            let name = rest.strip_suffix(']').ok_or_else(|| invalid_config_line(trimmed))?;
+            section = Some(name.trim().to_string());
+            last_entry = LastEntry::None;
             continue;
         }
 
-        if let Some(eq_pos) = line.find('=') {
-            // per-name default: name = path
-            let name = line[..eq_pos].trim().to_string();
-            let path = line[eq_pos + 1..].trim().to_string();
-            if !name.is_empty() && !path.is_empty() {
-                config.defaults.insert(name, path);
+        let is_default_line = match section.as_deref() {
+            Some("patterns") | Some("ignore") => false,
+            Some("defaults") => true,
+            _ => trimmed.contains('='),
+        };
+
+        if is_default_line {
+            let eq_pos = trimmed.find('=').ok_or_else(|| invalid_config_line(trimmed))?;
+            let name = trimmed[..eq_pos].trim().to_string();
+            let value = trimmed[eq_pos + 1..].trim().to_string();
+            if name.is_empty() || value.is_empty() {
+                return Err(invalid_config_line(trimmed));
             }
+            config.defaults.insert(name, value);
+            last_entry = LastEntry::Default;
+        } else if section.as_deref() == Some("ignore") {
+            add_ignore_pattern(config, trimmed)?;
+            last_entry = LastEntry::Ignore;
         } else {
-            // glob pattern (no '=')
-            let pattern = Pattern::new(line).map_err(|e| {
-                io::Error::new(io::ErrorKind::InvalidData, e.msg)
-            })?;
-            config.patterns.push(pattern);
+            add_pattern(config, trimmed)?;
+            last_entry = LastEntry::Pattern;
         }
     }
 
-    Ok(config)
+    Ok(())
 }
 
-/// Try to resolve multiple matches using config (per-name defaults and glob patterns).
+/// Try to resolve multiple matches using config (per-name defaults and patterns).
 /// Returns Some(index) if a match is found, None otherwise.
 fn resolve_default(
     config: &Config,
@@ -109,10 +286,14 @@ fn resolve_default(
         }
     }
 
-    // then check glob patterns (first match wins)
-    for pattern in &config.patterns {
+    // then check patterns in listed order (first match wins); negated patterns don't
+    // designate a default, they only exclude codebases from ever being returned
+    for entry in &config.patterns {
+        if entry.negated {
+            continue;
+        }
         for (i, path) in codebases.iter().enumerate() {
-            if pattern.matches_path(path) {
+            if entry.matcher.matches(path) {
                 return Some(i);
             }
         }
@@ -130,9 +311,28 @@ fn main() {
         (@arg CODEBASE: +required "Codebase to search, with optional /subdir")
         (@arg FILTER: "String to filter by, or line index to return")
         (@arg rebuild: -f --rebuild "Force rebuild of cache")
+        (@arg jobs: -j --jobs +takes_value "Max number of threads to use when scanning (default: all cores)")
     )
     .get_matches();
 
+    let jobs = match matches.value_of("jobs") {
+        Some(s) => match usize::from_str(s) {
+            Ok(n) => Some(n),
+            Err(_) => {
+                let _ = writeln!(
+                    std::io::stderr(),
+                    "{} invalid value for --jobs: {:?}",
+                    Red.bold().paint("error:"),
+                    s
+                );
+                std::process::exit(1);
+            }
+        },
+        None => std::env::var("CODESWITCH_JOBS")
+            .ok()
+            .and_then(|s| usize::from_str(&s).ok()),
+    };
+
     let dirpath: &Path = Path::new(matches.value_of_os("DIR").unwrap());
     let filter: &OsStr = matches
         .value_of_os("FILTER")
@@ -155,21 +355,36 @@ fn main() {
         subdir,
         filter,
         matches.is_present("rebuild"),
+        jobs,
     ) {
         let _ = writeln!(std::io::stderr(), "{} {}", Red.bold().paint("error:"), e);
         std::process::exit(1);
     }
 }
 
+/// Build a rayon thread pool capped at `jobs` threads, or rayon's default (all cores) if None.
+fn build_thread_pool(jobs: Option<usize>) -> io::Result<rayon::ThreadPool> {
+    let mut builder = rayon::ThreadPoolBuilder::new();
+    if let Some(n) = jobs {
+        builder = builder.num_threads(n);
+    }
+    builder.build().map_err(io::Error::other)
+}
+
 fn run(
     dirpath: &Path,
     wanted_codebase: &OsStr,
     subdir: Option<&OsStr>,
     filter: &OsStr,
     force_rebuild: bool,
+    jobs: Option<usize>,
 ) -> io::Result<()> {
-    let config = read_config()?;
+    let mut config = read_config()?;
     let dir = Dir::open(dirpath)?;
+    let pool = build_thread_pool(jobs)?;
+
+    let mut ignore = std::mem::take(&mut config.ignores);
+    ignore.extend(read_ignore_file(&dir)?);
 
     let meta = dir.metadata(".")?;
     if !meta.is_dir() {
@@ -194,10 +409,10 @@ fn run(
     let mut was_cached = false;
     let cachefn = cachedir.join(crate_name!());
     let mut codebases = if force_rebuild {
-        build_cache(&dir, &cachefn)?
+        pool.install(|| build_cache(&dir, &cachefn, &ignore))?
     } else {
         match read_cache(&dir, &cachefn)? {
-            Option::None => build_cache(&dir, &cachefn)?,
+            Option::None => pool.install(|| build_cache(&dir, &cachefn, &ignore))?,
             Option::Some(codebases) => {
                 was_cached = true;
                 codebases
@@ -205,6 +420,9 @@ fn run(
         }
     };
 
+    /* drop codebases matched by a negated (!) pattern before they can show up anywhere */
+    codebases.retain(|path| !is_excluded(&config, path));
+
     /* short-circuit for '_' support, e.g. for shell auto-completion */
     if wanted_codebase == "_" {
         /* add to set to make unique */
@@ -224,7 +442,8 @@ fn run(
 
     /* if we didn't find anything but the cache isn't fresh, let's try rescanning */
     if codebases.is_empty() && was_cached {
-        codebases = build_cache(&dir, &cachefn)?;
+        codebases = pool.install(|| build_cache(&dir, &cachefn, &ignore))?;
+        codebases.retain(|path| !is_excluded(&config, path));
         codebases.retain(|path| path.ends_with(wanted_codebase));
     }
 
@@ -303,6 +522,28 @@ fn print_codebases(dir: &Path, codebases: &[PathBuf]) -> io::Result<()> {
     Ok(())
 }
 
+/* versioned, self-validating cache format: a magic string + format version gate
+ * unknown/stale formats, each codebase carries the mtime of its `.git` dir, and every
+ * non-codebase directory the scan descends into carries its own mtime, so adding or
+ * removing an entry anywhere the scan descends is detectable without a full rescan:
+ * whichever directory it happened in is in this list, and its mtime will have moved
+ * (changes inside a codebase's own working tree or inside an ignored dir are not tracked) */
+const CACHE_MAGIC: &[u8] = b"codeswitch-cache\0";
+const CACHE_VERSION: u32 = 3;
+
+struct CacheHeader {
+    dev: u64,
+    ino: u64,
+    codebase_count: u32,
+    dir_count: u32,
+}
+
+struct CacheEntry {
+    path: PathBuf,
+    mtime: i64,
+    mtime_nsec: i64,
+}
+
 fn read_cache(cached_dir: &Dir, cache: &Path) -> io::Result<Option<Vec<PathBuf>>> {
     match fs::File::open(cache) {
         Err(e) => {
@@ -316,70 +557,188 @@ fn read_cache(cached_dir: &Dir, cache: &Path) -> io::Result<Option<Vec<PathBuf>>
     }
 }
 
+fn read_cache_header(reader: &mut impl BufRead) -> io::Result<Option<CacheHeader>> {
+    let mut magic = vec![0u8; CACHE_MAGIC.len()];
+    if let Err(e) = reader.read_exact(&mut magic) {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            return Ok(None);
+        }
+        return Err(e);
+    }
+    if magic != CACHE_MAGIC {
+        /* unrecognized format (or pre-versioning cache); fall back to rebuild */
+        return Ok(None);
+    }
+
+    let version = reader.read_u32::<LittleEndian>()?;
+    if version != CACHE_VERSION {
+        return Ok(None);
+    }
+
+    let codebase_count = reader.read_u32::<LittleEndian>()?;
+    let dir_count = reader.read_u32::<LittleEndian>()?;
+    let dev = reader.read_u64::<LittleEndian>()?;
+    let ino = reader.read_u64::<LittleEndian>()?;
+
+    Ok(Some(CacheHeader {
+        dev,
+        ino,
+        codebase_count,
+        dir_count,
+    }))
+}
+
+fn read_cache_entries(reader: &mut impl BufRead, count: u32) -> io::Result<Vec<CacheEntry>> {
+    let mut entries = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let len = reader.read_u32::<LittleEndian>()? as usize;
+        let mut buf = vec![0u8; len];
+        reader.read_exact(&mut buf)?;
+        let mtime = reader.read_i64::<LittleEndian>()?;
+        let mtime_nsec = reader.read_i64::<LittleEndian>()?;
+        entries.push(CacheEntry {
+            path: PathBuf::from(OsString::from_vec(buf)),
+            mtime,
+            mtime_nsec,
+        });
+    }
+    Ok(entries)
+}
+
 fn read_cache_file(cached_dir: &Dir, file: &fs::File) -> io::Result<Option<Vec<PathBuf>>> {
+    let mut reader = io::BufReader::new(file);
+
+    let header = match read_cache_header(&mut reader)? {
+        Some(header) => header,
+        None => return Ok(None),
+    };
+
+    /* dev/inode identifies the scanned root regardless of the path used to reach it (e.g.
+     * in my pet container, I use /code, outside ~/Code) */
     let meta = cached_dir.metadata(".")?;
     let stat = meta.stat();
+    if header.dev != stat.st_dev || header.ino != stat.st_ino {
+        return Ok(None);
+    }
 
-    let mut reader = io::BufReader::new(file);
-
-    /* first read dev and inode and check that they match */
-    let cached_dev = reader.read_u64::<LittleEndian>()?;
-    let cached_ino = reader.read_u64::<LittleEndian>()?;
+    let codebases = read_cache_entries(&mut reader, header.codebase_count)?;
+    let dirs = read_cache_entries(&mut reader, header.dir_count)?;
+    if codebases.is_empty() {
+        return Ok(None);
+    }
 
-    if cached_dev != stat.st_dev || cached_ino != stat.st_ino {
+    if is_cache_stale(cached_dir, &codebases, &dirs)? {
         return Ok(None);
     }
 
-    let mut codebases = Vec::new();
-    loop {
-        let mut buf = Vec::new();
-        let n = reader.read_until(b'\0', &mut buf)?;
-        if n == 0 {
-            if codebases.is_empty() {
-                return Ok(None);
-            }
-            return Ok(Some(codebases));
+    Ok(Some(codebases.into_iter().map(|e| e.path).collect()))
+}
+
+/// Cheap staleness check: re-stat the recorded codebases' `.git` dirs (did an existing
+/// codebase's `.git` change?) and every directory that was visited while scanning (did
+/// anything get added to or removed from it, anywhere in the tree?), instead of
+/// rescanning the whole tree. A single mismatch anywhere is enough to call it stale; if
+/// every recorded mtime still matches, the tree is unchanged and the cache is trusted.
+fn is_cache_stale(dir: &Dir, codebases: &[CacheEntry], dirs: &[CacheEntry]) -> io::Result<bool> {
+    for entry in codebases {
+        if entry_mtime_changed(dir, &entry.path.join(".git"), entry)? {
+            return Ok(true);
+        }
+    }
+    for entry in dirs {
+        if entry_mtime_changed(dir, &entry.path, entry)? {
+            return Ok(true);
         }
+    }
+    Ok(false)
+}
 
-        /* trim tail */
-        while !buf.is_empty() && buf[buf.len() - 1] == b'\0' {
-            buf.pop();
+fn entry_mtime_changed(dir: &Dir, stat_path: &Path, entry: &CacheEntry) -> io::Result<bool> {
+    Ok(match dir.metadata(stat_path) {
+        Ok(meta) => {
+            let stat = meta.stat();
+            stat.st_mtime != entry.mtime || stat.st_mtime_nsec != entry.mtime_nsec
         }
+        Err(_) => true,
+    })
+}
 
-        codebases.push(PathBuf::from(OsString::from_vec(buf)));
-    }
+fn write_cache_entry(writer: &mut impl Write, path: &Path, mtime: i64, mtime_nsec: i64) -> io::Result<()> {
+    let bytes = path.as_os_str().as_bytes();
+    writer.write_u32::<LittleEndian>(bytes.len() as u32)?;
+    writer.write_all(bytes)?;
+    writer.write_i64::<LittleEndian>(mtime)?;
+    writer.write_i64::<LittleEndian>(mtime_nsec)?;
+    Ok(())
 }
 
-fn build_cache(cached_dir: &Dir, cache: &Path) -> io::Result<Vec<PathBuf>> {
+fn build_cache(cached_dir: &Dir, cache: &Path, ignore: &[Matcher]) -> io::Result<Vec<PathBuf>> {
     /* first, scan the target dir */
-    let codebases = scan_dir(cached_dir)?;
+    let (codebases, dirs) = scan_dir(cached_dir, ignore)?;
 
     /* ok, let's write it to cache */
 
     let file = fs::File::create(cache)?;
     let mut writer = io::BufWriter::new(file);
 
-    /* store cached dir inode first so it works regardless of different paths due to
-     * symlinks/bind-mounts (e.g. in my pet container, I use /code, outside ~/Code) */
     let meta = cached_dir.metadata(".")?;
     let stat = meta.stat();
+
+    writer.write_all(CACHE_MAGIC)?;
+    writer.write_u32::<LittleEndian>(CACHE_VERSION)?;
+    writer.write_u32::<LittleEndian>(codebases.len() as u32)?;
+    writer.write_u32::<LittleEndian>(dirs.len() as u32)?;
+    /* store cached dir dev/inode (after the magic, version and counts) so it works regardless of different paths due to
+     * symlinks/bind-mounts (e.g. in my pet container, I use /code, outside ~/Code) */
     writer.write_u64::<LittleEndian>(stat.st_dev)?;
     writer.write_u64::<LittleEndian>(stat.st_ino)?;
 
     for codebase in &codebases {
-        writer.write_all(codebase.as_os_str().as_bytes())?;
-        writer.write_all(b"\0")?;
+        let git_meta = cached_dir.metadata(&codebase.join(".git"))?;
+        let git_stat = git_meta.stat();
+        write_cache_entry(&mut writer, codebase, git_stat.st_mtime, git_stat.st_mtime_nsec)?;
+    }
+
+    /* every non-codebase directory the scan descended into gets its own entry too, so that
+     * an addition/removal anywhere the scan descends is detectable without walking the
+     * whole thing again */
+    for dir_entry in &dirs {
+        write_cache_entry(&mut writer, &dir_entry.path, dir_entry.mtime, dir_entry.mtime_nsec)?;
     }
 
     Ok(codebases)
 }
 
-fn scan_dir(dir: &Dir) -> io::Result<Vec<PathBuf>> {
-    let mut codebases = Vec::new();
-    /* Note here that the pathbuf stack we init is *not* initialized with a dirpath. The
-     * cache then purely holds paths relative to dir. */
-    scan_dir_recurse(dir, &mut PathBuf::new(), &mut codebases)?;
-    Ok(codebases)
+fn scan_dir(dir: &Dir, ignore: &[Matcher]) -> io::Result<(Vec<PathBuf>, Vec<CacheEntry>)> {
+    /* Note here that the path we init is *not* initialized with a dirpath. The cache then
+     * purely holds paths relative to dir. */
+    let (_, mut codebases, mut dirs) = scan_dir_recurse(dir, &PathBuf::new(), ignore)?;
+    /* parallel recursion completes in nondeterministic order, so sort at the top level to
+     * keep the printed numbered list and cache contents stable across runs */
+    codebases.sort();
+    dirs.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok((codebases, dirs))
+}
+
+/// Read the optional `.codeswitchignore` file at the scan root: one pattern per line, using
+/// the same prefix language as `[ignore]`/`[patterns]` config entries.
+fn read_ignore_file(dir: &Dir) -> io::Result<Vec<Matcher>> {
+    let file = match dir.open_file(".codeswitchignore") {
+        Ok(f) => f,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut matchers = Vec::new();
+    for line in io::BufReader::new(file).lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        matchers.push(parse_matcher(line)?);
+    }
+    Ok(matchers)
 }
 
 #[derive(PartialEq)]
@@ -388,11 +747,20 @@ enum DirType {
     Branch,
 }
 
+/* one child of a branch directory to recurse into: `path_name` is the path component to
+ * descend with (the symlink name, for a symlinked subdir), `real_name` is the actual
+ * subdir to open, and `is_symlink` says whether the two differ */
+struct Child {
+    path_name: OsString,
+    real_name: OsString,
+    is_symlink: bool,
+}
+
 fn scan_dir_recurse(
     dir: &Dir,
-    path: &mut PathBuf,
-    codebases: &mut Vec<PathBuf>,
-) -> io::Result<DirType> {
+    path: &Path,
+    ignore: &[Matcher],
+) -> io::Result<(DirType, Vec<PathBuf>, Vec<CacheEntry>)> {
     /* We want to return a list of subpaths which have a .git dir with symlinks substituted
      * into middle components if they're shorter. Leaf dirs (codebases) are always added
      * once using its real subdir and once using its symlink if exists */
@@ -404,8 +772,7 @@ fn scan_dir_recurse(
             }
         }
         Ok(_) => {
-            codebases.push(path.clone());
-            return Ok(DirType::Leaf);
+            return Ok((DirType::Leaf, vec![path.to_path_buf()], Vec::new()));
         }
     };
 
@@ -433,6 +800,11 @@ fn scan_dir_recurse(
         }
     }
 
+    /* prune directories matched by an ignore pattern before the symlink-shortening pass, so
+     * an ignored target can't be resurrected by a shorter symlink alias pointing at it */
+    subdirs.retain(|name| !is_ignored(ignore, &path.join(name)));
+    symlinks.retain(|link, _target| !is_ignored(ignore, &path.join(link)));
+
     /* prune away dead symlinks */
     symlinks.retain(|_, target| subdirs.contains(target));
 
@@ -441,23 +813,312 @@ fn scan_dir_recurse(
         subdirs.remove(target);
     }
 
-    /* recurse into symlinks */
-    for (symlink, target) in &symlinks {
-        path.push(symlink);
-        let dtype = scan_dir_recurse(&dir.sub_dir(target.as_os_str())?, path, codebases)?;
-        path.pop();
-        /* make sure we also add the non-symlink version if it was a codebase */
-        if dtype == DirType::Leaf {
-            codebases.push(path.join(target));
+    /* flatten into a single child list so we can recurse into all of them in parallel;
+     * each branch task gets its own Vec<PathBuf> which we concatenate once all are done */
+    let mut children: Vec<Child> = Vec::with_capacity(symlinks.len() + subdirs.len());
+    for (symlink, target) in symlinks {
+        children.push(Child {
+            path_name: symlink,
+            real_name: target,
+            is_symlink: true,
+        });
+    }
+    for subdir in subdirs {
+        children.push(Child {
+            real_name: subdir.clone(),
+            path_name: subdir,
+            is_symlink: false,
+        });
+    }
+
+    let results: Vec<io::Result<(Vec<PathBuf>, Vec<CacheEntry>)>> = children
+        .into_par_iter()
+        .map(|child| {
+            let child_path = path.join(&child.path_name);
+            let (dtype, mut found, dirs) = scan_dir_recurse(
+                &dir.sub_dir(child.real_name.as_os_str())?,
+                &child_path,
+                ignore,
+            )?;
+            /* make sure we also add the non-symlink version if it was a codebase */
+            if child.is_symlink && dtype == DirType::Leaf {
+                found.push(path.join(&child.real_name));
+            }
+            Ok((found, dirs))
+        })
+        .collect();
+
+    let mut codebases = Vec::new();
+    let mut dirs = Vec::new();
+    for result in results {
+        let (found, found_dirs) = result?;
+        codebases.extend(found);
+        dirs.extend(found_dirs);
+    }
+
+    /* track this directory's own mtime too, so that adding or removing an entry directly
+     * inside it (not just deep inside a leaf codebase) is detectable without a full rescan */
+    let meta = dir.metadata(".")?;
+    let stat = meta.stat();
+    let self_path = if path.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        path.to_path_buf()
+    };
+    dirs.push(CacheEntry {
+        path: self_path,
+        mtime: stat.st_mtime,
+        mtime_nsec: stat.st_mtime_nsec,
+    });
+
+    Ok((DirType::Branch, codebases, dirs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    fn stat_entry(dir: &Dir, path: &str) -> CacheEntry {
+        let meta = dir.metadata(path).unwrap();
+        let stat = meta.stat();
+        CacheEntry {
+            path: PathBuf::from(path),
+            mtime: stat.st_mtime,
+            mtime_nsec: stat.st_mtime_nsec,
+        }
+    }
+
+    #[test]
+    fn cache_not_stale_when_nothing_changed() {
+        let tmp = tempdir();
+        fs::create_dir_all(tmp.join("proj/.git")).unwrap();
+        let dir = Dir::open(&*tmp).unwrap();
+
+        let codebases = vec![stat_entry(&dir, "proj")];
+        let dirs = vec![stat_entry(&dir, ".")];
+
+        assert!(!is_cache_stale(&dir, &codebases, &dirs).unwrap());
+    }
+
+    #[test]
+    fn cache_stale_when_codebase_git_dir_changes() {
+        let tmp = tempdir();
+        fs::create_dir_all(tmp.join("proj/.git")).unwrap();
+        let dir = Dir::open(&*tmp).unwrap();
+
+        let codebases = vec![stat_entry(&dir, "proj")];
+        let dirs = vec![stat_entry(&dir, ".")];
+
+        sleep(Duration::from_millis(1010));
+        fs::write(tmp.join("proj/.git/HEAD"), "ref: refs/heads/main").unwrap();
+
+        assert!(is_cache_stale(&dir, &codebases, &dirs).unwrap());
+    }
+
+    #[test]
+    fn cache_stale_when_nested_dir_gains_new_entry() {
+        let tmp = tempdir();
+        fs::create_dir_all(tmp.join("sub/deep")).unwrap();
+        let dir = Dir::open(&*tmp).unwrap();
+
+        /* only the root codebase list is recorded; "sub/deep" is tracked purely as a
+         * visited directory, same as a fresh scan would record it */
+        let codebases = Vec::new();
+        let dirs = vec![stat_entry(&dir, "."), stat_entry(&dir, "sub/deep")];
+
+        sleep(Duration::from_millis(1010));
+        fs::create_dir(tmp.join("sub/deep/newcodebase")).unwrap();
+
+        assert!(is_cache_stale(&dir, &codebases, &dirs).unwrap());
+    }
+
+    #[test]
+    fn cache_stale_when_tracked_dir_removed() {
+        let tmp = tempdir();
+        fs::create_dir_all(tmp.join("sub")).unwrap();
+        let dir = Dir::open(&*tmp).unwrap();
+
+        let dirs = vec![stat_entry(&dir, "."), CacheEntry {
+            path: PathBuf::from("sub/gone"),
+            mtime: 0,
+            mtime_nsec: 0,
+        }];
+
+        assert!(is_cache_stale(&dir, &Vec::new(), &dirs).unwrap());
+    }
+
+    #[test]
+    fn include_resolves_relative_to_including_file() {
+        let tmp = tempdir();
+        fs::create_dir_all(tmp.join("sub")).unwrap();
+        fs::write(tmp.join("sub/included.conf"), "[defaults]\nfoo = /bar\n").unwrap();
+        fs::write(tmp.join("main.conf"), "%include sub/included.conf\n").unwrap();
+
+        let mut config = Config::new();
+        let mut visited = HashSet::new();
+        load_config_file(&tmp.join("main.conf"), &mut config, &mut visited).unwrap();
+
+        assert_eq!(config.defaults.get("foo").map(String::as_str), Some("/bar"));
+    }
+
+    #[test]
+    fn include_cycle_terminates() {
+        let tmp = tempdir();
+        fs::write(tmp.join("a.conf"), "%include b.conf\n").unwrap();
+        fs::write(tmp.join("b.conf"), "%include a.conf\n").unwrap();
+
+        let mut config = Config::new();
+        let mut visited = HashSet::new();
+        load_config_file(&tmp.join("a.conf"), &mut config, &mut visited).unwrap();
+    }
+
+    #[test]
+    fn unset_removes_a_prior_default() {
+        let tmp = tempdir();
+        fs::write(tmp.join("codeswitch.conf"), "[defaults]\nfoo = /bar\n%unset foo\n").unwrap();
+
+        let mut config = Config::new();
+        let mut visited = HashSet::new();
+        load_config_file(&tmp.join("codeswitch.conf"), &mut config, &mut visited).unwrap();
+
+        assert_eq!(config.defaults.get("foo"), None);
+    }
+
+    #[test]
+    fn sections_scope_entries_to_the_right_list() {
+        let tmp = tempdir();
+        fs::write(
+            tmp.join("codeswitch.conf"),
+            "[defaults]\nfoo = /bar\n[patterns]\nglob:proj*\n[ignore]\nnode_modules\n",
+        )
+        .unwrap();
+
+        let mut config = Config::new();
+        let mut visited = HashSet::new();
+        load_config_file(&tmp.join("codeswitch.conf"), &mut config, &mut visited).unwrap();
+
+        assert_eq!(config.defaults.get("foo").map(String::as_str), Some("/bar"));
+        assert_eq!(config.patterns.len(), 1);
+        assert!(config.patterns[0].matcher.matches(Path::new("proj1")));
+        assert_eq!(config.ignores.len(), 1);
+        assert!(config.ignores[0].matches(Path::new("node_modules")));
+    }
+
+    #[test]
+    fn continuation_extends_patterns_but_rejects_defaults() {
+        let tmp = tempdir();
+        fs::write(
+            tmp.join("codeswitch.conf"),
+            "[patterns]\nglob:proj*\n  glob:extra*\n",
+        )
+        .unwrap();
+
+        let mut config = Config::new();
+        let mut visited = HashSet::new();
+        load_config_file(&tmp.join("codeswitch.conf"), &mut config, &mut visited).unwrap();
+        assert_eq!(config.patterns.len(), 2);
+
+        fs::write(
+            tmp.join("bad.conf"),
+            "[defaults]\nfoo = /bar\n  baz\n",
+        )
+        .unwrap();
+
+        let mut config = Config::new();
+        let mut visited = HashSet::new();
+        assert!(load_config_file(&tmp.join("bad.conf"), &mut config, &mut visited).is_err());
+    }
+
+    #[test]
+    fn bare_ignore_pattern_prunes_at_any_depth() {
+        let ignore = vec![parse_matcher("node_modules").unwrap()];
+
+        assert!(is_ignored(&ignore, Path::new("node_modules")));
+        assert!(is_ignored(&ignore, Path::new("sub/deep/node_modules")));
+        assert!(!is_ignored(&ignore, Path::new("sub/node_modules_extra")));
+    }
+
+    #[test]
+    fn slash_ignore_pattern_only_matches_full_path() {
+        let ignore = vec![parse_matcher("glob:**/node_modules").unwrap()];
+
+        assert!(is_ignored(&ignore, Path::new("node_modules")));
+        assert!(is_ignored(&ignore, Path::new("sub/deep/node_modules")));
+    }
+
+    #[test]
+    fn rootfilesin_matches_only_direct_children() {
+        let matcher = parse_matcher("rootfilesin:projects").unwrap();
+
+        assert!(matcher.matches(Path::new("projects/foo")));
+        assert!(!matcher.matches(Path::new("projects/sub/foo")));
+        assert!(!matcher.matches(Path::new("other/foo")));
+    }
+
+    #[test]
+    fn path_prefix_anchors_at_the_root_not_as_a_suffix() {
+        let matcher = parse_matcher("path:projects/foo").unwrap();
+
+        assert!(matcher.matches(Path::new("projects/foo")));
+        assert!(matcher.matches(Path::new("projects/foo/bar")));
+        assert!(!matcher.matches(Path::new("projects/foobar")));
+        assert!(!matcher.matches(Path::new("other/projects/foo")));
+    }
+
+    #[test]
+    fn regex_matches_against_the_full_relative_path() {
+        let matcher = parse_matcher("re:^proj").unwrap();
+
+        assert!(matcher.matches(Path::new("project1")));
+        assert!(!matcher.matches(Path::new("other/project1")));
+    }
+
+    #[test]
+    fn negated_pattern_excludes_but_does_not_designate_a_default() {
+        let mut config = Config::new();
+        /* listed in order: a negated pattern that would match the first candidate if it
+         * weren't skipped, then a non-negated pattern that matches the second */
+        add_pattern(&mut config, "!path:teams/other").unwrap();
+        add_pattern(&mut config, "path:teams/core").unwrap();
+
+        assert!(is_excluded(&config, Path::new("teams/other/proj")));
+        assert!(!is_excluded(&config, Path::new("teams/core/proj")));
+
+        let codebases = vec![
+            PathBuf::from("teams/other/proj"),
+            PathBuf::from("teams/core/proj"),
+        ];
+        assert_eq!(
+            resolve_default(&config, OsStr::new("proj"), &codebases),
+            Some(1)
+        );
+    }
+
+    /* minimal helper: a fresh, uniquely-named directory under the OS temp dir, removed on drop */
+    struct TempDir(PathBuf);
+
+    impl std::ops::Deref for TempDir {
+        type Target = Path;
+        fn deref(&self) -> &Path {
+            &self.0
         }
     }
 
-    /* recurse into the other subdirs */
-    for subdir in &subdirs {
-        path.push(subdir);
-        scan_dir_recurse(&dir.sub_dir(subdir.as_os_str())?, path, codebases)?;
-        path.pop();
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
     }
 
-    Ok(DirType::Branch)
+    fn tempdir() -> TempDir {
+        use std::sync::atomic::{AtomicU32, Ordering};
+        static COUNTER: AtomicU32 = AtomicU32::new(0);
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let pid = std::process::id();
+        let path = std::env::temp_dir().join(format!("codeswitch-test-{}-{}", pid, n));
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
 }